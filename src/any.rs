@@ -0,0 +1,111 @@
+use async_std::sync::RwLockWriteGuard;
+use tide::utils::async_trait;
+use tide::Request;
+
+use sqlx::any::Any;
+
+#[cfg(all(feature = "tracing", debug_assertions))]
+use tracing_crate::{debug_span, Instrument};
+
+use crate::{run_in_savepoint, ConnectionWrap, ConnectionWrapInner, SQLxMiddleware};
+
+/// An alias for `tide_sqlx::SQLxMiddleware<Any>`.
+#[allow(dead_code)]
+pub type AnyMiddleware = SQLxMiddleware<Any>;
+
+/// An extension trait for [tide::Request][] which does proper unwrapping of the connection from [`req.ext()`][].
+///
+/// Specialized for [`sqlx::Any`][], letting a single binary target whatever backend a connection
+/// URL specifies at runtime (Postgres, MySQL, SQLite, ...) without threading a concrete `DB` type
+/// parameter through every `sqlx_conn::<DB>()` call.
+///
+/// [`req.ext()`]: https://docs.rs/tide/0.15.0/tide/struct.Request.html#method.ext
+/// [tide::Request]: https://docs.rs/tide/0.15.0/tide/struct.Request.html
+/// [`sqlx::Any`]: https://docs.rs/sqlx/latest/sqlx/any/struct.Any.html
+#[cfg(feature = "any")]
+#[cfg_attr(feature = "docs", doc(cfg(feature = "any")))]
+#[async_trait]
+pub trait AnyRequestExt {
+    /// Get the SQLx connection for the current Request.
+    ///
+    /// This will return a "write" guard from a read-write lock.
+    /// Under the hood this will transparently be either a transaction or a direct pooled connection.
+    ///
+    /// This will panic with an expect message if the `SQLxMiddleware` has not been run.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// # #[async_std::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// # use tide_sqlx::any::AnyMiddleware;
+    /// # use sqlx::any::Any;
+    /// #
+    /// # let mut app = tide::new();
+    /// # app.with(AnyMiddleware::new("postgres://localhost/a_database").await?);
+    /// #
+    /// use sqlx::Acquire; // Or sqlx::prelude::*;
+    ///
+    /// use tide_sqlx::any::AnyRequestExt;
+    ///
+    /// app.at("/").post(|req: tide::Request<()>| async move {
+    ///     let mut any_conn = req.any_conn().await;
+    ///
+    ///     sqlx::query("SELECT * FROM users")
+    ///         .fetch_optional(any_conn.acquire().await?)
+    ///         .await;
+    ///
+    ///     Ok("")
+    /// });
+    /// # Ok(())
+    /// # }
+    /// ```
+    async fn any_conn<'req>(&'req self) -> RwLockWriteGuard<'req, ConnectionWrapInner<Any>>;
+
+    /// Run `callback` as a scoped sub-transaction on the request's already-held connection.
+    ///
+    /// See [`SQLxRequestExt::sqlx_transaction`][] for the full behavior.
+    ///
+    /// [`SQLxRequestExt::sqlx_transaction`]: ../trait.SQLxRequestExt.html#tymethod.sqlx_transaction
+    async fn any_transaction<'req, F, Fut, R, E>(
+        &'req self,
+        callback: F,
+    ) -> std::result::Result<R, E>
+    where
+        F: for<'c> FnOnce(&'c mut <Any as sqlx::Database>::Connection) -> Fut + Send,
+        Fut: std::future::Future<Output = std::result::Result<R, E>> + Send,
+        R: Send,
+        E: From<sqlx::Error> + Send;
+}
+
+#[async_trait]
+impl<T: Send + Sync + 'static> AnyRequestExt for Request<T> {
+    async fn any_conn<'req>(&'req self) -> RwLockWriteGuard<'req, ConnectionWrapInner<Any>> {
+        let any_conn: &ConnectionWrap<Any> = self
+            .ext()
+            .expect("You must install SQLx middleware providing Any ConnectionWrap");
+        let rwlock_fut = any_conn.write();
+        #[cfg(all(feature = "tracing", debug_assertions))]
+        let rwlock_fut =
+            rwlock_fut.instrument(debug_span!("Any connection RwLockWriteGuard acquire"));
+        rwlock_fut.await
+    }
+
+    async fn any_transaction<'req, F, Fut, R, E>(
+        &'req self,
+        callback: F,
+    ) -> std::result::Result<R, E>
+    where
+        F: for<'c> FnOnce(&'c mut <Any as sqlx::Database>::Connection) -> Fut + Send,
+        Fut: std::future::Future<Output = std::result::Result<R, E>> + Send,
+        R: Send,
+        E: From<sqlx::Error> + Send,
+    {
+        let any_conn: &ConnectionWrap<Any> = self
+            .ext()
+            .expect("You must install SQLx middleware providing Any ConnectionWrap");
+        let mut sqlx_conn = any_conn.write().await;
+
+        run_in_savepoint(&mut *sqlx_conn, callback).await
+    }
+}
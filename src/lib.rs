@@ -2,7 +2,9 @@
 //! each [tide::Request][] a connection, which may transparently be either a database transaction,
 //! or a direct pooled database connection.
 //!
-//! By default, transactions are used for all http methods other than `GET` and `HEAD`.
+//! By default, transactions are used for all http methods other than `GET` and `HEAD`; this can
+//! be overridden per-request via [`SQLxMiddleware::builder`][]'s
+//! [`SQLxMiddlewareBuilder::transaction_when`][] method.
 //!
 //! When using this, use the `SQLxRequestExt` extenstion trait to get the connection.
 //!
@@ -78,25 +80,24 @@
 //!
 //! [tide::Request]: https://docs.rs/tide/0.15.0/tide/struct.Request.html
 //! [Tide]: https://docs.rs/tide/0.15.0/tide/
+//! [`SQLxMiddleware::builder`]: struct.SQLxMiddleware.html#method.builder
+//! [`SQLxMiddlewareBuilder::transaction_when`]: struct.SQLxMiddlewareBuilder.html#method.transaction_when
 
 #![cfg_attr(feature = "docs", feature(doc_cfg))]
 
 use std::fmt::{self, Debug};
 use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use async_std::sync::{RwLock, RwLockWriteGuard};
 use sqlx::pool::{Pool, PoolConnection};
-use sqlx::{Database, Transaction};
+use sqlx::{Connection, Database, Executor, Transaction};
+use tide::http;
 use tide::http::Method;
 use tide::utils::async_trait;
 use tide::{Middleware, Next, Request, Result};
 
-#[cfg(feature = "unsafe-nested-transactions")]
-use sqlx::Connection;
-#[cfg(feature = "unsafe-nested-transactions")]
-use tide::http;
-
 #[cfg(all(test, not(feature = "postgres")))]
 compile_error!("The tests must be run with --features=test");
 
@@ -105,13 +106,26 @@ compile_error!("The tests must be run with --features=test");
 /// Helpers specific to Postgres
 pub mod postgres;
 
+#[cfg(feature = "any")]
+#[cfg_attr(feature = "docs", doc(cfg(feature = "any")))]
+/// Helpers for [`sqlx::Any`][], letting the backend be chosen at runtime from the connection URL.
+///
+/// [`sqlx::Any`]: https://docs.rs/sqlx/latest/sqlx/any/struct.Any.html
+pub mod any;
+
 #[doc(hidden)]
 pub enum ConnectionWrapInner<DB>
 where
     DB: Database,
     DB::Connection: Send + Sync + 'static,
 {
-    Transacting(Transaction<'static, DB>),
+    /// A transaction, along with the depth of nested SAVEPOINTs currently live within it.
+    ///
+    /// The outermost request to start the transaction holds depth `0`; with the
+    /// `nested-transactions` feature enabled, each nested request (detected via an
+    /// already-present [`ConnectionWrap`][]) pushes a `SAVEPOINT` and bumps this by one, popping
+    /// it back off before returning.
+    Transacting(Transaction<'static, DB>, u32),
     Plain(PoolConnection<DB>),
 }
 
@@ -122,7 +136,10 @@ where
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Transacting(_) => f.debug_struct("ConnectionWrapInner::Transacting").finish(),
+            Self::Transacting(_, depth) => f
+                .debug_struct("ConnectionWrapInner::Transacting")
+                .field("depth", depth)
+                .finish(),
             Self::Plain(_) => f.debug_struct("ConnectionWrapInner::Plain").finish(),
         }
     }
@@ -138,7 +155,7 @@ where
     fn deref(&self) -> &Self::Target {
         match self {
             ConnectionWrapInner::Plain(c) => c,
-            ConnectionWrapInner::Transacting(c) => c,
+            ConnectionWrapInner::Transacting(c, _) => c,
         }
     }
 }
@@ -151,7 +168,7 @@ where
     fn deref_mut(&mut self) -> &mut Self::Target {
         match self {
             ConnectionWrapInner::Plain(c) => c,
-            ConnectionWrapInner::Transacting(c) => c,
+            ConnectionWrapInner::Transacting(c, _) => c,
         }
     }
 }
@@ -159,11 +176,18 @@ where
 #[doc(hidden)]
 pub type ConnectionWrap<DB> = Arc<RwLock<ConnectionWrapInner<DB>>>;
 
+/// A flag, stored in `req.ext()` alongside the [`ConnectionWrap`][], letting an endpoint mark the
+/// current request's transaction for rollback even once a successful response has been produced.
+#[doc(hidden)]
+pub type RollbackFlag = Arc<AtomicBool>;
+
 /// This middleware holds a pool of SQLx database connections, and automatically hands each
 /// [tide::Request][] a connection, which may transparently be either a database transaction,
 /// or a direct pooled database connection.
 ///
-/// By default, transactions are used for all http methods other than `GET` and `HEAD`.
+/// By default, transactions are used for all http methods other than `GET` and `HEAD`; this can
+/// be overridden per-request via [`SQLxMiddleware::builder`][]'s
+/// [`SQLxMiddlewareBuilder::transaction_when`][] method.
 ///
 /// When using this, use the `SQLxRequestExt` extenstion trait to get the connection.
 ///
@@ -195,13 +219,47 @@ pub type ConnectionWrap<DB> = Arc<RwLock<ConnectionWrapInner<DB>>>;
 /// ```
 ///
 /// [tide::Request]: https://docs.rs/tide/0.15.0/tide/struct.Request.html
-#[derive(Debug, Clone)]
+/// [`SQLxMiddleware::builder`]: struct.SQLxMiddleware.html#method.builder
+/// [`SQLxMiddlewareBuilder::transaction_when`]: struct.SQLxMiddlewareBuilder.html#method.transaction_when
 pub struct SQLxMiddleware<DB>
 where
     DB: Database,
     DB::Connection: Send + Sync + 'static,
 {
     pool: Pool<DB>,
+    transaction_when: Arc<dyn Fn(&tide::http::Request) -> bool + Send + Sync>,
+    test_before_acquire: Option<u32>,
+}
+
+impl<DB> Debug for SQLxMiddleware<DB>
+where
+    DB: Database,
+    DB::Connection: Send + Sync + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SQLxMiddleware")
+            .field("pool", &self.pool)
+            .finish()
+    }
+}
+
+impl<DB> Clone for SQLxMiddleware<DB>
+where
+    DB: Database,
+    DB::Connection: Send + Sync + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            transaction_when: self.transaction_when.clone(),
+            test_before_acquire: self.test_before_acquire,
+        }
+    }
+}
+
+/// The default transaction policy: transact for every method other than `GET`/`HEAD`.
+fn default_transaction_when(req: &tide::http::Request) -> bool {
+    !matches!(req.method(), Method::Get | Method::Head)
 }
 
 impl<DB> SQLxMiddleware<DB>
@@ -212,7 +270,22 @@ where
     /// Create a new instance of `SQLxMiddleware`.
     pub async fn new(pgurl: &'_ str) -> std::result::Result<Self, sqlx::Error> {
         let pool: Pool<DB> = Pool::connect(pgurl).await?;
-        Ok(Self { pool })
+        Ok(Self {
+            pool,
+            transaction_when: Arc::new(default_transaction_when),
+            test_before_acquire: None,
+        })
+    }
+
+    /// Create a [`SQLxMiddlewareBuilder`][] to configure a `SQLxMiddleware` beyond the defaults.
+    ///
+    /// [`SQLxMiddlewareBuilder`]: struct.SQLxMiddlewareBuilder.html
+    pub fn builder(pool: Pool<DB>) -> SQLxMiddlewareBuilder<DB> {
+        SQLxMiddlewareBuilder {
+            pool,
+            transaction_when: None,
+            test_before_acquire: None,
+        }
     }
 }
 
@@ -223,7 +296,90 @@ where
 {
     /// Create a new instance of `SQLxMiddleware` from a `sqlx::Pool`.
     fn from(pool: Pool<DB>) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            transaction_when: Arc::new(default_transaction_when),
+            test_before_acquire: None,
+        }
+    }
+}
+
+/// A builder for [`SQLxMiddleware`][], allowing the transaction policy to be overridden.
+///
+/// Created via [`SQLxMiddleware::builder`][].
+///
+/// [`SQLxMiddleware`]: struct.SQLxMiddleware.html
+/// [`SQLxMiddleware::builder`]: struct.SQLxMiddleware.html#method.builder
+pub struct SQLxMiddlewareBuilder<DB>
+where
+    DB: Database,
+    DB::Connection: Send + Sync + 'static,
+{
+    pool: Pool<DB>,
+    transaction_when: Option<Arc<dyn Fn(&tide::http::Request) -> bool + Send + Sync>>,
+    test_before_acquire: Option<u32>,
+}
+
+impl<DB> SQLxMiddlewareBuilder<DB>
+where
+    DB: Database,
+    DB::Connection: Send + Sync + 'static,
+{
+    /// Override how this middleware decides whether to start a transaction for a request.
+    ///
+    /// By default, a transaction is started for every method other than `GET`/`HEAD`. This
+    /// allows that decision to be based on any request attribute instead, e.g. a path prefix
+    /// or a custom header.
+    ///
+    /// The predicate only sees the HTTP-level [`tide::http::Request`][] (method, path, headers,
+    /// ...), not the full [`tide::Request`][]`<State>` this middleware runs on, so it cannot
+    /// inspect app state or `req.ext()`. This is a deliberate scope reduction, not just an
+    /// oversight: a `Fn(&tide::Request<State>) -> bool` predicate would need `SQLxMiddleware`
+    /// itself to carry a `State` type parameter, which is a bigger change than this method
+    /// justifies on its own. Path- and header-based policies (the common case) work fine; a
+    /// policy that needs to key off app state or something stashed in `req.ext()` by earlier
+    /// middleware cannot be expressed here yet.
+    ///
+    /// [`tide::http::Request`]: https://docs.rs/http-types/2.12.0/http_types/struct.Request.html
+    /// [`tide::Request`]: https://docs.rs/tide/0.15.0/tide/struct.Request.html
+    pub fn transaction_when<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&tide::http::Request) -> bool + Send + Sync + 'static,
+    {
+        self.transaction_when = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Opt in to health-checking pooled connections before handing them to the endpoint.
+    ///
+    /// When enabled, a freshly-acquired connection is [`ping`][]ed before use; if the ping
+    /// fails (e.g. the connection died while idle in the pool), it is not handed to the
+    /// endpoint, and another connection is acquired, up to `max_retries` additional attempts,
+    /// before the request fails with the underlying error. On the direct-connection path the
+    /// failed connection is explicitly closed rather than returned to the pool's idle queue; on
+    /// the transaction path the failed transaction is rolled back and dropped, which returns its
+    /// connection to the pool instead of closing it. This adds an extra round-trip per request,
+    /// so it is off by default.
+    ///
+    /// Note that `sqlx::pool::PoolOptions::test_before_acquire` already pings on acquire by
+    /// default, so this mostly matters for explicitly bounding how many dead connections are
+    /// retried before giving up, rather than for the ping itself.
+    ///
+    /// [`ping`]: https://docs.rs/sqlx/latest/sqlx/trait.Connection.html#tymethod.ping
+    pub fn test_before_acquire(mut self, max_retries: u32) -> Self {
+        self.test_before_acquire = Some(max_retries);
+        self
+    }
+
+    /// Build the configured `SQLxMiddleware`.
+    pub fn build(self) -> SQLxMiddleware<DB> {
+        SQLxMiddleware {
+            pool: self.pool,
+            transaction_when: self
+                .transaction_when
+                .unwrap_or_else(|| Arc::new(default_transaction_when)),
+            test_before_acquire: self.test_before_acquire,
+        }
     }
 }
 
@@ -252,72 +408,50 @@ where
     State: Clone + Send + Sync + 'static,
     DB: Database,
     DB::Connection: Send + Sync + 'static,
+    for<'e> &'e mut DB::Connection: Executor<'e, Database = DB>,
 {
     async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> Result {
-        // TODO(Fishrock): Allow this to be overridden somehow. Maybe check part of the path.
-        let is_safe = match req.method() {
-            Method::Get => true,
-            Method::Head => true,
-            _ => false,
+        let is_safe = {
+            let inner_req: &http::Request = req.as_ref();
+            !(self.transaction_when)(inner_req)
         };
 
-        let conn_wrap_inner = if req.ext::<ConnectionWrap<DB>>().is_some() {
-            #[cfg(feature = "unsafe-nested-transactions")]
+        if req.ext::<ConnectionWrap<DB>>().is_some() {
+            #[cfg(feature = "nested-transactions")]
             {
-                let inner_req: &mut http::Request = req.as_mut();
-                let conn_wrap = inner_req
-                    .ext_mut()
-                    .remove::<ConnectionWrap<DB>>()
+                let conn_wrap = req
+                    .ext::<ConnectionWrap<DB>>()
+                    .cloned()
                     .expect("This was literally just checked.");
-                let mut sqlx_conn = conn_wrap.write().await;
-
-                let nested_trans = sqlx_conn.begin().await?;
-
-                // UNSAFE.
-                //
-                // Rust is presently unable to express the guarentees we need here.
-                //
-                // Tide is meant to run on Async-Std, which uses a _threaded futures executor_.
-                // A threaded futures executor requires that all Futures be `Send`, so that they can be sent across thread bounadries.
-                // `Send` implies that any lifetimes must be `'static`. This is actually the limitation in Rust.
-                // Why `'static'` is implied is answered here: https://stackoverflow.com/a/26783347
-                // In short:
-                // `Send` implies that said object carries no reference to the current thread stack.
-                // If an object has no reference to the current stack, its lifetime bound is `'static`.
-                //
-                // However Rust already has to ensure that other Futures scopes outlive inner futures scopes even in the case of a threaded executor.
-                // So in this case `Send` could theoretically hold a lifetime of an outer scope and still be sound.
-                // In practice, async closures, once implemented by the language, will have to do exactly this same thing to work with threaded executors.
-                // When that happens and Tide moves to being able to use async closures, this will no longer be useful, thankfully.
-                //
-                // So, since Rust already enforces most of the memory safety we'd need anyways, this should be "safe" to do.
-                //
-                // Also, storing anything attached from middleware is already going to cause panics regardless, because that would mean an `Arc` would be unwrapped twice.
-                // This lifetime nonsense is of course still worse in that you may not immediately panic but will suffer from arbitrary memory corruption.
-                unsafe {
-                    ConnectionWrapInner::Transacting(extend_transaction_lifetime(nested_trans))
-                }
-                // End unsafe.
+                return handle_nested(conn_wrap, req, next).await;
             }
-            #[cfg(not(feature = "unsafe-nested-transactions"))]
+            #[cfg(not(feature = "nested-transactions"))]
             {
                 // Dual-purpose: Avoid ever running twice, or pick up a test connection if one exists.
                 return Ok(next.run(req).await);
             }
-        } else if is_safe {
-            ConnectionWrapInner::Plain(self.pool.acquire().await?)
+        }
+
+        let conn_wrap_inner = if is_safe {
+            ConnectionWrapInner::Plain(acquire_plain(&self.pool, self.test_before_acquire).await?)
         } else {
-            ConnectionWrapInner::Transacting(self.pool.begin().await?)
+            ConnectionWrapInner::Transacting(
+                acquire_transacting(&self.pool, self.test_before_acquire).await?,
+                0,
+            )
         };
         let conn_wrap = Arc::new(RwLock::new(conn_wrap_inner));
         req.set_ext(conn_wrap.clone());
+        let rollback_flag: RollbackFlag = Arc::new(AtomicBool::new(false));
+        req.set_ext(rollback_flag.clone());
 
         let res = next.run(req).await;
 
-        if res.error().is_none() {
+        if res.error().is_none() && !rollback_flag.load(Ordering::SeqCst) {
             if let Ok(conn_wrap_inner) = Arc::try_unwrap(conn_wrap) {
-                if let ConnectionWrapInner::Transacting(connection) = conn_wrap_inner.into_inner() {
-                    // if we errored, sqlx::Transaction calls rollback on Drop.
+                if let ConnectionWrapInner::Transacting(connection, _) = conn_wrap_inner.into_inner()
+                {
+                    // if we errored or were marked for rollback, sqlx::Transaction calls rollback on Drop.
                     connection.commit().await?;
                 }
             } else {
@@ -334,6 +468,145 @@ where
     }
 }
 
+/// Handle a request for which a [`ConnectionWrap`][] is already present in `req.ext()`, i.e. this
+/// middleware is mounted more than once in the same request's chain (for example on a nested
+/// sub-app). Rather than opening a second, independent transaction, this pushes a `SAVEPOINT`
+/// onto the existing one so that a failure in the nested scope only unwinds the nested work,
+/// leaving the outer transaction intact.
+#[cfg(feature = "nested-transactions")]
+async fn handle_nested<State, DB>(
+    conn_wrap: ConnectionWrap<DB>,
+    req: Request<State>,
+    next: Next<'_, State>,
+) -> Result
+where
+    State: Clone + Send + Sync + 'static,
+    DB: Database,
+    DB::Connection: Send + Sync + 'static,
+    for<'e> &'e mut DB::Connection: Executor<'e, Database = DB>,
+{
+    let depth = {
+        let mut sqlx_conn = conn_wrap.write().await;
+        match &mut *sqlx_conn {
+            ConnectionWrapInner::Transacting(connection, depth) => {
+                let next_depth = *depth + 1;
+                connection
+                    .execute(format!("SAVEPOINT _tide_sqlx_sp_{}", next_depth).as_str())
+                    .await?;
+                // Only commit the depth bump now that the SAVEPOINT has actually been created.
+                *depth = next_depth;
+                next_depth
+            }
+            // There's no outer transaction to nest within; nothing to roll back either way.
+            ConnectionWrapInner::Plain(_) => return Ok(next.run(req).await),
+        }
+    };
+
+    let res = next.run(req).await;
+
+    {
+        let mut sqlx_conn = conn_wrap.write().await;
+        if let ConnectionWrapInner::Transacting(connection, current_depth) = &mut *sqlx_conn {
+            let finish = if res.error().is_none() {
+                connection
+                    .execute(format!("RELEASE SAVEPOINT _tide_sqlx_sp_{}", depth).as_str())
+                    .await
+            } else {
+                match connection
+                    .execute(format!("ROLLBACK TO SAVEPOINT _tide_sqlx_sp_{}", depth).as_str())
+                    .await
+                {
+                    Ok(_) => {
+                        connection
+                            .execute(format!("RELEASE SAVEPOINT _tide_sqlx_sp_{}", depth).as_str())
+                            .await
+                    }
+                    Err(err) => Err(err),
+                }
+            };
+            // Restore the depth unconditionally before propagating any finishing error, so a
+            // failed RELEASE/ROLLBACK never leaves the counter permanently off.
+            *current_depth -= 1;
+            finish?;
+        }
+    }
+
+    Ok(res)
+}
+
+/// Run `callback` as a scoped sub-transaction on an already-held connection, shared by
+/// [`SQLxRequestExt::sqlx_transaction`][], `PostgresRequestExt::pg_transaction`, and
+/// `AnyRequestExt::any_transaction`.
+///
+/// If `sqlx_conn` is a transaction, this opens a SAVEPOINT before running `callback`, releasing
+/// it if `callback` resolves to `Ok`, or rolling back to it if `callback` resolves to `Err`. The
+/// depth counter on `sqlx_conn` is only advanced once the `SAVEPOINT` has actually been issued
+/// successfully, and is always restored afterwards regardless of how the SAVEPOINT is finished,
+/// so a failure partway through never leaves it permanently out of sync.
+///
+/// If `sqlx_conn` is a direct pooled connection, there is no surrounding transaction to scope a
+/// SAVEPOINT to, so `callback` is simply run directly.
+///
+/// [`SQLxRequestExt::sqlx_transaction`]: trait.SQLxRequestExt.html#tymethod.sqlx_transaction
+pub(crate) async fn run_in_savepoint<DB, F, Fut, R, E>(
+    sqlx_conn: &mut ConnectionWrapInner<DB>,
+    callback: F,
+) -> std::result::Result<R, E>
+where
+    DB: Database,
+    DB::Connection: Send + Sync + 'static,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    F: for<'c> FnOnce(&'c mut DB::Connection) -> Fut + Send,
+    Fut: std::future::Future<Output = std::result::Result<R, E>> + Send,
+    R: Send,
+    E: From<sqlx::Error> + Send,
+{
+    let depth = match sqlx_conn {
+        ConnectionWrapInner::Transacting(_, depth) => Some(*depth + 1),
+        ConnectionWrapInner::Plain(_) => None,
+    };
+
+    if let Some(depth) = depth {
+        sqlx_conn
+            .execute(format!("SAVEPOINT _tide_sqlx_sp_{}", depth).as_str())
+            .await
+            .map_err(E::from)?;
+        if let ConnectionWrapInner::Transacting(_, current_depth) = sqlx_conn {
+            *current_depth = depth;
+        }
+    }
+
+    let result = callback(&mut *sqlx_conn).await;
+
+    if let Some(depth) = depth {
+        let finish = if result.is_ok() {
+            sqlx_conn
+                .execute(format!("RELEASE SAVEPOINT _tide_sqlx_sp_{}", depth).as_str())
+                .await
+        } else {
+            match sqlx_conn
+                .execute(format!("ROLLBACK TO SAVEPOINT _tide_sqlx_sp_{}", depth).as_str())
+                .await
+            {
+                Ok(_) => {
+                    sqlx_conn
+                        .execute(format!("RELEASE SAVEPOINT _tide_sqlx_sp_{}", depth).as_str())
+                        .await
+                }
+                Err(err) => Err(err),
+            }
+        };
+
+        if let ConnectionWrapInner::Transacting(_, current_depth) = sqlx_conn {
+            *current_depth = depth - 1;
+        }
+
+        finish.map_err(E::from)?;
+    }
+
+    result
+}
+
 /// An extension trait for [tide::Request][] which does proper unwrapping of the connection from [`req.ext()`][].
 ///
 /// [`req.ext()`]: https://docs.rs/tide/0.15.0/tide/struct.Request.html#method.ext
@@ -378,6 +651,41 @@ pub trait SQLxRequestExt {
     where
         DB: Database,
         DB::Connection: Send + Sync + 'static;
+
+    /// Run `callback` as a scoped sub-transaction on the request's already-held connection.
+    ///
+    /// This opens a SAVEPOINT on the connection [`sqlx_conn`][] would return, releasing it if
+    /// `callback` resolves to `Ok`, or rolling back to it if `callback` resolves to `Err`, then
+    /// returns whatever `callback` returned. This gives an endpoint assured, composable rollback
+    /// for a discrete unit of work without depending on the request-wide commit/rollback
+    /// behaviour of `SQLxMiddleware`, and without manually juggling `acquire()`.
+    ///
+    /// If the request's connection is a direct pooled connection rather than a transaction (i.e.
+    /// the request was deemed safe, see [`SQLxMiddlewareBuilder::transaction_when`][]), there is
+    /// no surrounding transaction to scope a SAVEPOINT to, so `callback` is simply run directly.
+    ///
+    /// [`sqlx_conn`]: #tymethod.sqlx_conn
+    /// [`SQLxMiddlewareBuilder::transaction_when`]: struct.SQLxMiddlewareBuilder.html#method.transaction_when
+    async fn sqlx_transaction<'req, DB, F, Fut, R, E>(
+        &'req self,
+        callback: F,
+    ) -> std::result::Result<R, E>
+    where
+        DB: Database,
+        DB::Connection: Send + Sync + 'static,
+        for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+        F: for<'c> FnOnce(&'c mut DB::Connection) -> Fut + Send,
+        Fut: std::future::Future<Output = std::result::Result<R, E>> + Send,
+        R: Send,
+        E: From<sqlx::Error> + Send;
+
+    /// Mark the current request's transaction to be rolled back once the response is returned,
+    /// even if that response turns out to be a success.
+    ///
+    /// By default, `SQLxMiddleware` only rolls back when the response carries an error; this
+    /// lets an endpoint that still returns e.g. `200 OK` abort the transaction anyway after
+    /// detecting a business-logic problem. Has no effect if `SQLxMiddleware` has not been run.
+    fn sqlx_mark_rollback(&self);
 }
 
 #[async_trait]
@@ -392,23 +700,154 @@ impl<T: Send + Sync + 'static> SQLxRequestExt for Request<T> {
             .expect("You must install SQLx middleware providing ConnectionWrap");
         sqlx_conn.write().await
     }
+
+    async fn sqlx_transaction<'req, DB, F, Fut, R, E>(
+        &'req self,
+        callback: F,
+    ) -> std::result::Result<R, E>
+    where
+        DB: Database,
+        DB::Connection: Send + Sync + 'static,
+        for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+        F: for<'c> FnOnce(&'c mut DB::Connection) -> Fut + Send,
+        Fut: std::future::Future<Output = std::result::Result<R, E>> + Send,
+        R: Send,
+        E: From<sqlx::Error> + Send,
+    {
+        let conn_wrap: &ConnectionWrap<DB> = self
+            .ext()
+            .expect("You must install SQLx middleware providing ConnectionWrap");
+        let mut sqlx_conn = conn_wrap.write().await;
+
+        run_in_savepoint(&mut *sqlx_conn, callback).await
+    }
+
+    fn sqlx_mark_rollback(&self) {
+        if let Some(flag) = self.ext::<RollbackFlag>() {
+            flag.store(true, Ordering::SeqCst);
+        }
+    }
 }
 
-/// EXTREMELY UNSAFE. MAY LEAD TO MEMORY CORRUPTION.
+/// Acquire a pooled connection, optionally health-checking it with [`Connection::ping`][] before
+/// handing it back, retrying up to `test_before_acquire` times if the ping fails.
 ///
-/// See https://doc.rust-lang.org/std/mem/fn.transmute.html for more information.
+/// A connection that fails its ping is explicitly closed rather than simply dropped: dropping a
+/// `PoolConnection` returns it to the pool's idle queue via its normal `Drop` impl, which would
+/// let the retry hand back that exact same dead connection (or worse, let it be handed to some
+/// unrelated future request).
 ///
-/// This is made even more unsafe than just `transmute<'a, 'static>` because `sqlx::Transaction` has an enum internally,
-/// which does not have a known size, and so `transmute_copy` is required even though we aren't changing the enum varient internally.
-/// Presumably this is a limitation of Rust that may be solved in the future.
+/// [`Connection::ping`]: https://docs.rs/sqlx/latest/sqlx/trait.Connection.html#tymethod.ping
+async fn acquire_plain<DB>(
+    pool: &Pool<DB>,
+    test_before_acquire: Option<u32>,
+) -> std::result::Result<PoolConnection<DB>, sqlx::Error>
+where
+    DB: Database,
+    DB::Connection: Send + Sync + 'static,
+{
+    let max_retries = match test_before_acquire {
+        Some(max_retries) => max_retries,
+        None => return pool.acquire().await,
+    };
+
+    let mut last_err = None;
+    for _ in 0..=max_retries {
+        let mut conn = pool.acquire().await?;
+        match conn.ping().await {
+            Ok(()) => return Ok(conn),
+            Err(err) => {
+                last_err = Some(err);
+                let _ = conn.close().await;
+            }
+        }
+    }
+    Err(last_err.expect("loop runs at least once"))
+}
+
+/// As [`acquire_plain`][], but for a freshly-started transaction.
 ///
-/// See https://doc.rust-lang.org/std/mem/fn.transmute_copy.html for even more specific information.
-#[cfg(feature = "unsafe-nested-transactions")]
-unsafe fn extend_transaction_lifetime<'c, DB>(
-    transaction: Transaction<'c, DB>,
-) -> Transaction<'static, DB>
+/// Unlike [`acquire_plain`][], a transaction that fails its ping is rolled back and dropped, not
+/// closed, so its connection goes back to the pool's idle queue and a retry may be handed that
+/// same connection again.
+async fn acquire_transacting<DB>(
+    pool: &Pool<DB>,
+    test_before_acquire: Option<u32>,
+) -> std::result::Result<Transaction<'static, DB>, sqlx::Error>
 where
     DB: Database,
+    DB::Connection: Send + Sync + 'static,
 {
-    std::mem::transmute_copy::<Transaction<'c, DB>, Transaction<'static, DB>>(&transaction)
+    let max_retries = match test_before_acquire {
+        Some(max_retries) => max_retries,
+        None => return pool.begin().await,
+    };
+
+    let mut last_err = None;
+    for _ in 0..=max_retries {
+        let mut trans = pool.begin().await?;
+        match trans.ping().await {
+            Ok(()) => return Ok(trans),
+            Err(err) => {
+                last_err = Some(err);
+                let _ = trans.rollback().await;
+            }
+        }
+    }
+    Err(last_err.expect("loop runs at least once"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::{Sqlite, SqlitePool};
+
+    async fn transacting_sqlite() -> ConnectionWrapInner<Sqlite> {
+        let pool = SqlitePool::connect(":memory:")
+            .await
+            .expect("in-memory sqlite pool");
+        let txn = pool.begin().await.expect("begin transaction");
+        ConnectionWrapInner::Transacting(txn, 0)
+    }
+
+    fn depth_of(conn: &ConnectionWrapInner<Sqlite>) -> u32 {
+        match conn {
+            ConnectionWrapInner::Transacting(_, depth) => *depth,
+            ConnectionWrapInner::Plain(_) => panic!("expected a transacting connection"),
+        }
+    }
+
+    #[async_std::test]
+    async fn depth_is_restored_after_a_successful_savepoint() {
+        let mut conn = transacting_sqlite().await;
+
+        run_in_savepoint(&mut conn, |_conn| {
+            Box::pin(async { Ok::<(), sqlx::Error>(()) })
+        })
+        .await
+        .expect("savepoint should succeed");
+
+        // RELEASE ran, so depth is back to 0, not left incremented.
+        assert_eq!(depth_of(&conn), 0);
+    }
+
+    #[async_std::test]
+    async fn depth_is_restored_even_when_the_finishing_sql_fails() {
+        let mut conn = transacting_sqlite().await;
+
+        // The callback releases the SAVEPOINT itself, so `run_in_savepoint`'s own RELEASE at the
+        // end fails because `_tide_sqlx_sp_1` no longer exists. Before the `run_in_savepoint` fix
+        // in this series, a failure here would have left `depth` permanently incremented.
+        let result: std::result::Result<(), sqlx::Error> = run_in_savepoint(&mut conn, |conn| {
+            Box::pin(async move {
+                conn.execute("RELEASE SAVEPOINT _tide_sqlx_sp_1").await?;
+                Ok(())
+            })
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(depth_of(&conn), 0);
+    }
 }
+
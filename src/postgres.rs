@@ -7,7 +7,7 @@ use sqlx::postgres::Postgres;
 #[cfg(all(feature = "tracing", debug_assertions))]
 use tracing_crate::{debug_span, Instrument};
 
-use crate::{ConnectionWrap, ConnectionWrapInner, SQLxMiddleware};
+use crate::{run_in_savepoint, ConnectionWrap, ConnectionWrapInner, SQLxMiddleware};
 
 /// An alias for `tide_sqlx::SQLxMiddleware<Postgres>`.
 #[allow(dead_code)]
@@ -58,6 +58,22 @@ pub trait PostgresRequestExt {
     /// # }
     /// ```
     async fn pg_conn<'req>(&'req self) -> RwLockWriteGuard<'req, ConnectionWrapInner<Postgres>>;
+
+    /// Run `callback` as a scoped sub-transaction on the request's already-held Postgres
+    /// connection.
+    ///
+    /// See [`SQLxRequestExt::sqlx_transaction`][] for the full behavior.
+    ///
+    /// [`SQLxRequestExt::sqlx_transaction`]: ../trait.SQLxRequestExt.html#tymethod.sqlx_transaction
+    async fn pg_transaction<'req, F, Fut, R, E>(
+        &'req self,
+        callback: F,
+    ) -> std::result::Result<R, E>
+    where
+        F: for<'c> FnOnce(&'c mut <Postgres as sqlx::Database>::Connection) -> Fut + Send,
+        Fut: std::future::Future<Output = std::result::Result<R, E>> + Send,
+        R: Send,
+        E: From<sqlx::Error> + Send;
 }
 
 #[async_trait]
@@ -72,4 +88,22 @@ impl<T: Send + Sync + 'static> PostgresRequestExt for Request<T> {
             rwlock_fut.instrument(debug_span!("Postgres connection RwLockWriteGuard acquire"));
         rwlock_fut.await
     }
+
+    async fn pg_transaction<'req, F, Fut, R, E>(
+        &'req self,
+        callback: F,
+    ) -> std::result::Result<R, E>
+    where
+        F: for<'c> FnOnce(&'c mut <Postgres as sqlx::Database>::Connection) -> Fut + Send,
+        Fut: std::future::Future<Output = std::result::Result<R, E>> + Send,
+        R: Send,
+        E: From<sqlx::Error> + Send,
+    {
+        let pg_conn: &ConnectionWrap<Postgres> = self
+            .ext()
+            .expect("You must install SQLx middleware providing Postgres ConnectionWrap");
+        let mut sqlx_conn = pg_conn.write().await;
+
+        run_in_savepoint(&mut *sqlx_conn, callback).await
+    }
 }